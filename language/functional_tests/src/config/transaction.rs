@@ -2,13 +2,36 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{config::global::Config as GlobalConfig, errors::*, evaluator::Stage};
-use libra_types::transaction::{parse_as_transaction_argument, TransactionArgument};
-use std::{collections::BTreeSet, str::FromStr};
+use libra_types::{
+    account_address::AccountAddress,
+    account_config::LBR_NAME,
+    identifier::Identifier,
+    language_storage::{StructTag, TypeTag},
+    transaction::{parse_as_transaction_argument, RawTransaction, Script, TransactionArgument},
+};
+use std::{
+    collections::BTreeSet,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Max gas amount used to assemble a transaction when `//! max-gas:` is not set.
+const DEFAULT_MAX_GAS_AMOUNT: u64 = 1_000_000;
+
+/// Number of seconds from now a transaction is considered expired when
+/// `//! expiration-time:` is not set.
+const DEFAULT_EXPIRATION_TIME_SECS: u64 = 100;
+
+/// Currency codes minted in the functional-test genesis, i.e. the set of
+/// values `//! gas-currency:` is allowed to name.
+const KNOWN_CURRENCY_CODES: &[&str] = &[LBR_NAME, "Coin1", "Coin2"];
 
 /// A partially parsed transaction argument.
 #[derive(Debug)]
 pub enum Argument {
     AddressOf(String),
+    AuthKeyOf(String),
+    PublicKeyOf(String),
     SelfContained(TransactionArgument),
 }
 
@@ -20,20 +43,294 @@ impl FromStr for Argument {
             return Ok(Argument::SelfContained(arg));
         }
         if s.starts_with("{{") && s.ends_with("}}") {
-            return Ok(Argument::AddressOf(s[2..s.len() - 2].to_string()));
+            let inner = &s[2..s.len() - 2];
+            let mut parts = inner.splitn(2, "::");
+            let name = parts.next().unwrap().to_string();
+            return match parts.next() {
+                None => Ok(Argument::AddressOf(name)),
+                Some("authkey") => Ok(Argument::AuthKeyOf(name)),
+                Some("pubkey") => Ok(Argument::PublicKeyOf(name)),
+                Some(field) => Err(ErrorKind::Other(format!(
+                    "unknown placeholder field '{}' in '{}'",
+                    field, s
+                ))
+                .into()),
+            };
         }
         Err(ErrorKind::Other(format!("failed to parse '{}' as argument", s)).into())
     }
 }
 
+/// The address component of a struct type tag: either a literal address or a
+/// `{{name}}` placeholder to be resolved against a named account.
+#[derive(Debug, Clone)]
+enum AddressArg {
+    Literal(AccountAddress),
+    Placeholder(String),
+}
+
+impl AddressArg {
+    fn parse(s: &str) -> Result<Self> {
+        if s.starts_with("{{") && s.ends_with("}}") {
+            return Ok(AddressArg::Placeholder(s[2..s.len() - 2].to_string()));
+        }
+        match AccountAddress::from_hex_literal(s) {
+            Ok(addr) => Ok(AddressArg::Literal(addr)),
+            Err(_) => Err(ErrorKind::Other(format!(
+                "unknown primitive or malformed address '{}' in type tag",
+                s
+            ))
+            .into()),
+        }
+    }
+}
+
+/// A partially parsed struct type tag.
+#[derive(Debug, Clone)]
+struct StructTypeArg {
+    address: AddressArg,
+    module: String,
+    name: String,
+    type_params: Vec<TypeArg>,
+}
+
+/// A partially parsed `TypeTag`. Struct tags may carry a `{{name}}` address
+/// placeholder that is only resolvable once the surrounding `GlobalConfig` is known.
+#[derive(Debug, Clone)]
+pub enum TypeArg {
+    Bool,
+    U8,
+    U64,
+    U128,
+    Address,
+    Vector(Box<TypeArg>),
+    Struct(StructTypeArg),
+}
+
+impl TypeArg {
+    fn resolve(&self, config: &GlobalConfig) -> Result<TypeTag> {
+        Ok(match self {
+            TypeArg::Bool => TypeTag::Bool,
+            TypeArg::U8 => TypeTag::U8,
+            TypeArg::U64 => TypeTag::U64,
+            TypeArg::U128 => TypeTag::U128,
+            TypeArg::Address => TypeTag::Address,
+            TypeArg::Vector(inner) => TypeTag::Vector(Box::new(inner.resolve(config)?)),
+            TypeArg::Struct(s) => TypeTag::Struct(StructTag {
+                address: match &s.address {
+                    AddressArg::Literal(addr) => *addr,
+                    AddressArg::Placeholder(name) => match config.accounts.get(name) {
+                        Some(data) => *data.address(),
+                        None => {
+                            return Err(ErrorKind::Other(format!(
+                                "account '{}' does not exist",
+                                name
+                            ))
+                            .into())
+                        }
+                    },
+                },
+                module: Identifier::new(s.module.clone())
+                    .map_err(|e| ErrorKind::Other(e.to_string()))?,
+                name: Identifier::new(s.name.clone())
+                    .map_err(|e| ErrorKind::Other(e.to_string()))?,
+                type_params: s
+                    .type_params
+                    .iter()
+                    .map(|t| t.resolve(config))
+                    .collect::<Result<Vec<_>>>()?,
+            }),
+        })
+    }
+}
+
+/// Tokens produced when lexing a `//! type-args:` line.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Lt,
+    Gt,
+    Comma,
+    ColonColon,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    let mut buf = String::new();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '<' | '>' | ',' => {
+                if !buf.is_empty() {
+                    tokens.push(Token::Ident(buf.clone()));
+                    buf.clear();
+                }
+                tokens.push(if c == '<' {
+                    Token::Lt
+                } else if c == '>' {
+                    Token::Gt
+                } else {
+                    Token::Comma
+                });
+                chars.next();
+            }
+            ':' => {
+                chars.next();
+                if chars.next() != Some(':') {
+                    return Err(ErrorKind::Other("expected '::' in type tag".to_string()).into());
+                }
+                if !buf.is_empty() {
+                    tokens.push(Token::Ident(buf.clone()));
+                    buf.clear();
+                }
+                tokens.push(Token::ColonColon);
+            }
+            _ => {
+                buf.push(c);
+                chars.next();
+            }
+        }
+    }
+    if !buf.is_empty() {
+        tokens.push(Token::Ident(buf));
+    }
+    Ok(tokens)
+}
+
+/// A small recursive-descent parser for the Move type-tag grammar used by
+/// `//! type-args:`.
+struct TypeArgParser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> TypeArgParser<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn bump(&mut self) -> Option<&Token> {
+        let tok = self.tokens.get(self.pos);
+        self.pos += 1;
+        tok
+    }
+
+    fn expect_angle(&mut self, tok: Token) -> Result<()> {
+        match self.bump() {
+            Some(t) if *t == tok => Ok(()),
+            _ => Err(ErrorKind::Other("unbalanced angle brackets in type tag".to_string()).into()),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String> {
+        match self.bump() {
+            Some(Token::Ident(s)) => Ok(s.clone()),
+            Some(t) => {
+                Err(ErrorKind::Other(format!("expected identifier, found '{:?}'", t)).into())
+            }
+            None => {
+                Err(ErrorKind::Other("expected identifier, found end of input".to_string()).into())
+            }
+        }
+    }
+
+    fn parse_list(&mut self) -> Result<Vec<TypeArg>> {
+        let mut out = vec![self.parse_type_arg()?];
+        while let Some(Token::Comma) = self.peek() {
+            self.bump();
+            out.push(self.parse_type_arg()?);
+        }
+        Ok(out)
+    }
+
+    fn parse_type_arg(&mut self) -> Result<TypeArg> {
+        let ident = self.expect_ident()?;
+        match ident.as_str() {
+            "u8" => Ok(TypeArg::U8),
+            "u64" => Ok(TypeArg::U64),
+            "u128" => Ok(TypeArg::U128),
+            "bool" => Ok(TypeArg::Bool),
+            "address" => Ok(TypeArg::Address),
+            "vector" => {
+                self.expect_angle(Token::Lt)?;
+                let inner = self.parse_type_arg()?;
+                self.expect_angle(Token::Gt)?;
+                Ok(TypeArg::Vector(Box::new(inner)))
+            }
+            _ => {
+                let address = AddressArg::parse(&ident)?;
+                match self.bump() {
+                    Some(Token::ColonColon) => (),
+                    _ => {
+                        return Err(ErrorKind::Other(
+                            "expected '::' after address in struct type tag".to_string(),
+                        )
+                        .into())
+                    }
+                }
+                let module = self.expect_ident()?;
+                match self.bump() {
+                    Some(Token::ColonColon) => (),
+                    _ => {
+                        return Err(ErrorKind::Other(
+                            "expected '::' after module in struct type tag".to_string(),
+                        )
+                        .into())
+                    }
+                }
+                let name = self.expect_ident()?;
+                let type_params = if let Some(Token::Lt) = self.peek() {
+                    self.bump();
+                    let params = self.parse_list()?;
+                    self.expect_angle(Token::Gt)?;
+                    params
+                } else {
+                    vec![]
+                };
+                Ok(TypeArg::Struct(StructTypeArg {
+                    address,
+                    module,
+                    name,
+                    type_params,
+                }))
+            }
+        }
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.pos == self.tokens.len() {
+            Ok(())
+        } else {
+            Err(ErrorKind::Other("unbalanced angle brackets in type tag".to_string()).into())
+        }
+    }
+}
+
+fn parse_type_args(s: &str) -> Result<Vec<TypeArg>> {
+    let tokens = tokenize(s)?;
+    let mut parser = TypeArgParser::new(&tokens);
+    let args = parser.parse_list()?;
+    parser.finish()?;
+    Ok(args)
+}
+
 /// A raw entry extracted from the input. Used to build a transaction config table.
 #[derive(Debug)]
 pub enum Entry {
     DisableStages(Vec<Stage>),
     Sender(String),
+    SecondarySigners(Vec<String>),
     Arguments(Vec<Argument>),
+    TypeArguments(Vec<TypeArg>),
     MaxGas(u64),
+    GasUnitPrice(u64),
+    GasCurrency(String),
     SequenceNumber(u64),
+    ExpirationTime(u64),
 }
 
 impl FromStr for Entry {
@@ -54,6 +351,20 @@ impl FromStr for Entry {
             }
             return Ok(Entry::Sender(s.to_ascii_lowercase()));
         }
+        if s.starts_with("secondary-signers:") {
+            let res: Vec<_> = s[18..]
+                .split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_ascii_lowercase())
+                .collect();
+            if res.is_empty() {
+                return Err(
+                    ErrorKind::Other("secondary signers cannot be empty".to_string()).into(),
+                );
+            }
+            return Ok(Entry::SecondarySigners(res));
+        }
         if s.starts_with("args:") {
             let res: Result<Vec<_>> = s[5..]
                 .split(',')
@@ -63,6 +374,9 @@ impl FromStr for Entry {
                 .collect();
             return Ok(Entry::Arguments(res?));
         }
+        if s.starts_with("type-args:") {
+            return Ok(Entry::TypeArguments(parse_type_args(&s[10..])?));
+        }
         if s.starts_with("no-run:") {
             let res: Result<Vec<_>> = s[7..]
                 .split(',')
@@ -75,9 +389,22 @@ impl FromStr for Entry {
         if s.starts_with("max-gas:") {
             return Ok(Entry::MaxGas(s[8..].parse::<u64>()?));
         }
+        if s.starts_with("gas-price:") {
+            return Ok(Entry::GasUnitPrice(s[10..].parse::<u64>()?));
+        }
+        if s.starts_with("gas-currency:") {
+            let s = s[13..].trim_start().trim_end();
+            if s.is_empty() {
+                return Err(ErrorKind::Other("gas currency cannot be empty".to_string()).into());
+            }
+            return Ok(Entry::GasCurrency(s.to_string()));
+        }
         if s.starts_with("sequence-number:") {
             return Ok(Entry::SequenceNumber(s[16..].parse::<u64>()?));
         }
+        if s.starts_with("expiration-time:") {
+            return Ok(Entry::ExpirationTime(s[16..].parse::<u64>()?));
+        }
         Err(ErrorKind::Other(format!(
             "failed to parse '{}' as transaction config entry",
             s
@@ -111,9 +438,14 @@ impl Entry {
 pub struct Config {
     pub disabled_stages: BTreeSet<Stage>,
     pub sender: String,
+    pub secondary_signers: Vec<String>,
     pub args: Vec<TransactionArgument>,
+    pub type_args: Vec<TypeTag>,
     pub max_gas: Option<u64>,
+    pub gas_unit_price: Option<u64>,
+    pub gas_currency_code: Option<String>,
     pub sequence_number: Option<u64>,
+    pub expiration_time: Option<u64>,
 }
 
 impl Config {
@@ -121,9 +453,14 @@ impl Config {
     pub fn build(config: &GlobalConfig, entries: &[Entry]) -> Result<Self> {
         let mut disabled_stages = BTreeSet::new();
         let mut sender = None;
+        let mut secondary_signers = None;
         let mut args = None;
+        let mut type_args = None;
         let mut max_gas = None;
+        let mut gas_unit_price = None;
+        let mut gas_currency_code = None;
         let mut sequence_number = None;
+        let mut expiration_time = None;
 
         for entry in entries {
             match entry {
@@ -143,6 +480,35 @@ impl Config {
                     }
                     _ => return Err(ErrorKind::Other("sender already set".to_string()).into()),
                 },
+                Entry::SecondarySigners(names) => match secondary_signers {
+                    None => {
+                        let mut seen = BTreeSet::new();
+                        for name in names {
+                            if !config.accounts.contains_key(name)
+                                && !config.genesis_accounts.contains_key(name)
+                            {
+                                return Err(ErrorKind::Other(format!(
+                                    "account '{}' does not exist",
+                                    name
+                                ))
+                                .into());
+                            }
+                            if !seen.insert(name.clone()) {
+                                return Err(ErrorKind::Other(format!(
+                                    "duplicate secondary signer '{}'",
+                                    name
+                                ))
+                                .into());
+                            }
+                        }
+                        secondary_signers = Some(names.clone());
+                    }
+                    _ => {
+                        return Err(
+                            ErrorKind::Other("secondary signers already set".to_string()).into(),
+                        )
+                    }
+                },
                 Entry::Arguments(raw_args) => match args {
                     None => {
                         args = Some(
@@ -159,6 +525,28 @@ impl Config {
                                         ))
                                         .into()),
                                     },
+                                    Argument::AuthKeyOf(name) => match config.accounts.get(name) {
+                                        Some(data) => Ok(TransactionArgument::U8Vector(
+                                            data.authentication_key().to_vec(),
+                                        )),
+                                        None => Err(ErrorKind::Other(format!(
+                                            "account '{}' does not exist",
+                                            name
+                                        ))
+                                        .into()),
+                                    },
+                                    Argument::PublicKeyOf(name) => {
+                                        match config.accounts.get(name) {
+                                            Some(data) => Ok(TransactionArgument::U8Vector(
+                                                data.public_key().to_bytes().to_vec(),
+                                            )),
+                                            None => Err(ErrorKind::Other(format!(
+                                                "account '{}' does not exist",
+                                                name
+                                            ))
+                                            .into()),
+                                        }
+                                    }
                                     Argument::SelfContained(arg) => Ok(arg.clone()),
                                 })
                                 .collect::<Result<Vec<_>>>()?,
@@ -171,6 +559,21 @@ impl Config {
                         .into())
                     }
                 },
+                Entry::TypeArguments(raw_type_args) => match type_args {
+                    None => {
+                        type_args = Some(
+                            raw_type_args
+                                .iter()
+                                .map(|arg| arg.resolve(config))
+                                .collect::<Result<Vec<_>>>()?,
+                        );
+                    }
+                    _ => {
+                        return Err(
+                            ErrorKind::Other("type arguments already set".to_string()).into()
+                        )
+                    }
+                },
                 Entry::DisableStages(stages) => {
                     for stage in stages {
                         if !disabled_stages.insert(*stage) {
@@ -190,6 +593,30 @@ impl Config {
                         )
                     }
                 },
+                Entry::GasUnitPrice(price) => match gas_unit_price {
+                    None => gas_unit_price = Some(*price),
+                    Some(_) => {
+                        return Err(
+                            ErrorKind::Other("gas unit price already set".to_string()).into()
+                        )
+                    }
+                },
+                Entry::GasCurrency(code) => match gas_currency_code {
+                    None => {
+                        if KNOWN_CURRENCY_CODES.contains(&code.as_str()) {
+                            gas_currency_code = Some(code.to_string())
+                        } else {
+                            return Err(ErrorKind::Other(format!(
+                                "currency '{}' does not exist",
+                                code
+                            ))
+                            .into());
+                        }
+                    }
+                    Some(_) => {
+                        return Err(ErrorKind::Other("gas currency already set".to_string()).into())
+                    }
+                },
                 Entry::SequenceNumber(sn) => match sequence_number {
                     None => sequence_number = Some(*sn),
                     Some(_) => {
@@ -198,15 +625,38 @@ impl Config {
                         )
                     }
                 },
+                Entry::ExpirationTime(t) => match expiration_time {
+                    None => expiration_time = Some(*t),
+                    Some(_) => {
+                        return Err(
+                            ErrorKind::Other("expiration time already set".to_string()).into()
+                        )
+                    }
+                },
             }
         }
 
+        let sender = sender.unwrap_or_else(|| "default".to_string());
+        let secondary_signers = secondary_signers.unwrap_or_else(|| vec![]);
+        if secondary_signers.contains(&sender) {
+            return Err(ErrorKind::Other(format!(
+                "secondary signer '{}' duplicates the primary sender",
+                sender
+            ))
+            .into());
+        }
+
         Ok(Config {
             disabled_stages,
-            sender: sender.unwrap_or_else(|| "default".to_string()),
+            sender,
+            secondary_signers,
             args: args.unwrap_or_else(|| vec![]),
+            type_args: type_args.unwrap_or_else(|| vec![]),
             max_gas,
+            gas_unit_price,
+            gas_currency_code,
             sequence_number,
+            expiration_time,
         })
     }
 
@@ -214,4 +664,69 @@ impl Config {
     pub fn is_stage_disabled(&self, stage: Stage) -> bool {
         self.disabled_stages.contains(&stage)
     }
+
+    /// Builds the `Script` to be executed for this transaction, threading the
+    /// resolved type arguments through to the Move VM alongside the value arguments.
+    pub fn script(&self, code: Vec<u8>) -> Script {
+        Script::new(code, self.type_args.clone(), self.args.clone())
+    }
+
+    /// Assembles the `RawTransaction` for this config's sender, threading through
+    /// the gas unit price and currency so functional tests can assert fee behavior
+    /// under different currencies and prices.
+    pub fn build_raw_transaction(
+        &self,
+        sender_address: AccountAddress,
+        sequence_number: u64,
+        script: Script,
+    ) -> RawTransaction {
+        RawTransaction::new_script(
+            sender_address,
+            sequence_number,
+            script,
+            self.max_gas.unwrap_or(DEFAULT_MAX_GAS_AMOUNT),
+            self.gas_unit_price.unwrap_or(0),
+            self.gas_currency_code
+                .clone()
+                .unwrap_or_else(|| LBR_NAME.to_string()),
+            self.resolved_expiration_time(),
+        )
+    }
+
+    /// Resolves this transaction's expiration time, synthesizing a default of
+    /// `DEFAULT_EXPIRATION_TIME_SECS` seconds from now when `//! expiration-time:`
+    /// is not set, so scripts can still deliberately build already-expired or
+    /// far-future transactions via the directive.
+    fn resolved_expiration_time(&self) -> Duration {
+        match self.expiration_time {
+            Some(t) => Duration::from_secs(t),
+            None => {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("system time before UNIX epoch")
+                    + Duration::from_secs(DEFAULT_EXPIRATION_TIME_SECS)
+            }
+        }
+    }
+
+    /// Resolves this transaction's secondary signers into addresses, in order,
+    /// so the evaluator can assemble a multi-agent transaction alongside the
+    /// primary sender.
+    pub fn secondary_signer_addresses(&self, config: &GlobalConfig) -> Result<Vec<AccountAddress>> {
+        self.secondary_signers
+            .iter()
+            .map(|name| {
+                match config
+                    .accounts
+                    .get(name)
+                    .or_else(|| config.genesis_accounts.get(name))
+                {
+                    Some(data) => Ok(*data.address()),
+                    None => {
+                        Err(ErrorKind::Other(format!("account '{}' does not exist", name)).into())
+                    }
+                }
+            })
+            .collect()
+    }
 }